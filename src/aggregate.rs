@@ -0,0 +1,161 @@
+//! Rolling aggregation of per-peer traffic into a ranked "top talkers" report.
+//!
+//! `--aggregate` trades the raw per-sample `Output` blob for a trend report:
+//! instead of transcribing whatever iftop just measured, an `Aggregator`
+//! folds each sample's `Record`s into a time-bucketed queue keyed by host
+//! pair, evicts anything older than the window on every flush, and ranks
+//! what's left by total bytes moved.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde_derive::Serialize;
+
+use crate::Output;
+
+/// Running byte totals for a single `(local_name, remote_name)` pair.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Accumulator {
+    pub(crate) outbound_bytes: u64,
+    pub(crate) inbound_bytes: u64,
+}
+
+impl Accumulator {
+    fn total_bytes(&self) -> u64 {
+        self.outbound_bytes + self.inbound_bytes
+    }
+}
+
+/// One sample's contribution to a host pair, timestamped so it can be
+/// evicted once it falls outside the aggregation window.
+struct Bucket {
+    start_time: DateTime<Utc>,
+    local_name: String,
+    remote_name: String,
+    outbound_bytes: u64,
+    inbound_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TopTalker {
+    pub(crate) local_name: String,
+    pub(crate) remote_name: String,
+    pub(crate) outbound_bytes: u64,
+    pub(crate) inbound_bytes: u64,
+    pub(crate) total_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TopTalkersReport {
+    pub(crate) window_seconds: u64,
+    pub(crate) generated_at: DateTime<Utc>,
+    pub(crate) top_talkers: Vec<TopTalker>,
+}
+
+/// Accumulates per-peer byte counts over a sliding time window and produces
+/// a ranked "top talkers" report on demand.
+pub(crate) struct Aggregator {
+    window_seconds: u64,
+    top_n: usize,
+    buckets: Vec<Bucket>,
+    /// Last-seen cumulative (outbound, inbound) byte counters per peer, with
+    /// the start time of the sample they came from, so successive samples
+    /// can be differenced into bytes-per-interval.
+    last_cumulative: HashMap<(String, String), (u64, u64, DateTime<Utc>)>,
+}
+
+impl Aggregator {
+    pub(crate) fn new(window_seconds: u64, top_n: usize) -> Self {
+        Aggregator {
+            window_seconds,
+            top_n,
+            buckets: Vec::new(),
+            last_cumulative: HashMap::new(),
+        }
+    }
+
+    /// Fold a freshly-parsed `Output`'s records into the window.
+    ///
+    /// iftop's 2s/10s/40s columns are rolling-average *rates*, not bytes
+    /// transferred, and in streaming mode the same peer reappears in every
+    /// refresh - summing those columns directly would massively
+    /// double-count. Instead we difference each peer's cumulative byte
+    /// counter against the last sample we saw for it, so each bucket holds
+    /// only the bytes actually moved since that last sample.
+    pub(crate) fn ingest(&mut self, out: &Output) {
+        for record in &out.records {
+            let key = (record.local_name.clone(), record.remote_name.clone());
+            let (outbound_delta, inbound_delta) = match self.last_cumulative.get(&key) {
+                Some(&(last_outbound, last_inbound, _)) => (
+                    record.outbound_cumulative_bytes.saturating_sub(last_outbound),
+                    record.inbound_cumulative_bytes.saturating_sub(last_inbound),
+                ),
+                // First sample for this peer - nothing to difference against yet.
+                None => (0, 0),
+            };
+            self.last_cumulative.insert(
+                key.clone(),
+                (
+                    record.outbound_cumulative_bytes,
+                    record.inbound_cumulative_bytes,
+                    out.start_time,
+                ),
+            );
+
+            if outbound_delta == 0 && inbound_delta == 0 {
+                continue;
+            }
+
+            self.buckets.push(Bucket {
+                start_time: out.start_time,
+                local_name: key.0,
+                remote_name: key.1,
+                outbound_bytes: outbound_delta,
+                inbound_bytes: inbound_delta,
+            });
+        }
+    }
+
+    /// Evict buckets older than the window, then rank what's left. This
+    /// must run on every flush, even when nothing was just ingested, so
+    /// that peers which have gone quiet age out of the report.
+    pub(crate) fn flush(&mut self) -> TopTalkersReport {
+        let now = Utc::now();
+        let cutoff = now - Duration::seconds(self.window_seconds as i64);
+        self.buckets.retain(|b| b.start_time >= cutoff);
+
+        // Peers not seen in any sample within the window drop their last-seen
+        // counter; otherwise a long-running monitor that sees many distinct
+        // peers would grow this map without bound.
+        self.last_cumulative.retain(|_, last| last.2 >= cutoff);
+
+        let mut totals: HashMap<(String, String), Accumulator> = HashMap::new();
+        for bucket in &self.buckets {
+            let entry = totals
+                .entry((bucket.local_name.clone(), bucket.remote_name.clone()))
+                .or_default();
+            entry.outbound_bytes += bucket.outbound_bytes;
+            entry.inbound_bytes += bucket.inbound_bytes;
+        }
+
+        let mut top_talkers: Vec<TopTalker> = totals
+            .into_iter()
+            .map(|((local_name, remote_name), acc)| TopTalker {
+                local_name,
+                remote_name,
+                outbound_bytes: acc.outbound_bytes,
+                inbound_bytes: acc.inbound_bytes,
+                total_bytes: acc.total_bytes(),
+            })
+            .collect();
+        top_talkers.sort_unstable_by_key(|t| Reverse(t.total_bytes));
+        top_talkers.truncate(self.top_n);
+
+        TopTalkersReport {
+            window_seconds: self.window_seconds,
+            generated_at: now,
+            top_talkers,
+        }
+    }
+}
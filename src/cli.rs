@@ -0,0 +1,125 @@
+//! Command-line argument parsing.
+
+use clap::{Parser, ValueEnum};
+
+/// Run iftop in text mode and emit periodic summaries of the traffic it sees.
+#[derive(Debug, Parser)]
+#[command(name = "ifsummary", version, about)]
+pub(crate) struct Cli {
+    /// Path to the iftop binary.
+    #[arg(long, default_value = "/usr/sbin/iftop")]
+    pub(crate) iftop_path: String,
+
+    /// Network interface to monitor. Defaults to iftop's own default interface.
+    #[arg(short, long)]
+    pub(crate) interface: Option<String>,
+
+    /// Seconds iftop should sample before closing out a summary block. Only
+    /// meaningful with `--one-shot`; in the default streaming mode iftop
+    /// runs indefinitely and prints a block on its own refresh cadence.
+    #[arg(long, default_value_t = 40)]
+    pub(crate) sample_seconds: u32,
+
+    /// Run iftop with `-s sample-seconds` and exit after a single summary,
+    /// instead of streaming indefinitely from one long-running iftop process.
+    #[arg(long)]
+    pub(crate) one_shot: bool,
+
+    /// Which rolling-average column to rank peers by.
+    #[arg(long, value_enum, default_value_t = SortColumn::Forty)]
+    pub(crate) sort_column: SortColumn,
+
+    /// Output format for each emitted summary.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Ndjson)]
+    pub(crate) format: OutputFormat,
+
+    /// Retain the original human-formatted 40s byte strings alongside the
+    /// parsed values. The other columns keep only their parsed values, and
+    /// CSV output does not include the raw strings.
+    #[arg(long)]
+    pub(crate) keep_raw_bytes: bool,
+
+    /// Logging verbosity.
+    #[arg(long, value_enum, default_value_t = LogLevel::Info)]
+    pub(crate) log_level: LogLevel,
+
+    /// Instead of emitting one blob per iftop sample, accumulate per-peer
+    /// byte counts over a rolling window of this many seconds and emit a
+    /// ranked "top talkers" report every `--flush-seconds`.
+    #[arg(long, value_name = "SECONDS")]
+    pub(crate) aggregate: Option<u64>,
+
+    /// How many peers to retain in a `--aggregate` report.
+    #[arg(long, default_value_t = 10, requires = "aggregate")]
+    pub(crate) top_n: usize,
+
+    /// How often, in seconds, to evict stale peers and emit a fresh
+    /// `--aggregate` report. Runs on its own timer so peers age out of the
+    /// report even when the interface has gone quiet and no new sample has
+    /// arrived.
+    #[arg(long, default_value_t = 5, requires = "aggregate")]
+    pub(crate) flush_seconds: u64,
+
+    /// Extra arguments passed straight through to iftop, after `--`.
+    #[arg(last = true)]
+    pub(crate) extra_iftop_args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum SortColumn {
+    #[value(name = "2s")]
+    Two,
+    #[value(name = "10s")]
+    Ten,
+    #[value(name = "40s")]
+    Forty,
+}
+
+impl SortColumn {
+    /// The value iftop's `-o` flag expects.
+    pub(crate) fn as_iftop_arg(self) -> &'static str {
+        match self {
+            SortColumn::Two => "2s",
+            SortColumn::Ten => "10s",
+            SortColumn::Forty => "40s",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum OutputFormat {
+    Ndjson,
+    Csv,
+    Msgpack,
+}
+
+impl OutputFormat {
+    pub(crate) fn as_name(self) -> &'static str {
+        match self {
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Msgpack => "msgpack",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl From<LogLevel> for tracing::Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Trace => tracing::Level::TRACE,
+            LogLevel::Debug => tracing::Level::DEBUG,
+            LogLevel::Info => tracing::Level::INFO,
+            LogLevel::Warn => tracing::Level::WARN,
+            LogLevel::Error => tracing::Level::ERROR,
+        }
+    }
+}
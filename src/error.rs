@@ -0,0 +1,31 @@
+//! Error types surfaced while parsing iftop's textual output.
+
+use std::fmt;
+use std::num::ParseIntError;
+
+/// Something in the iftop output didn't match what we expected to see.
+///
+/// Most malformed lines are recoverable (we log a warning and skip them),
+/// but a rank column that overflows `u64` is surfaced here instead so the
+/// caller can decide how to react.
+#[derive(Debug)]
+pub(crate) enum ParseError {
+    /// The rank column (e.g. `"1"` in `"1 host => ..."`) wasn't a valid integer.
+    InvalidRank(ParseIntError),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidRank(e) => write!(f, "invalid rank column: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseIntError> for ParseError {
+    fn from(e: ParseIntError) -> Self {
+        ParseError::InvalidRank(e)
+    }
+}
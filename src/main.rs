@@ -1,15 +1,26 @@
-//! A tool to run iftop in text mode and output periodic summaries
+//! A tool to run iftop in text mode and stream periodic summaries of the
+//! traffic it sees, until told to stop.
 
 #[macro_use]
 extern crate lazy_static;
 
+mod aggregate;
+mod cli;
+mod error;
+mod format;
+
 use chrono::{DateTime, Utc};
-use regex::Regex;
+use clap::Parser;
+use regex::{Captures, Regex};
 use serde_derive::Serialize;
-use std::env;
-use std::ffi::{OsStr, OsString};
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, Write};
 use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use cli::Cli;
+use error::ParseError;
 
 lazy_static! {
     static ref R_SEPARATOR: Regex = Regex::new(r"^-+$").unwrap();
@@ -18,106 +29,388 @@ lazy_static! {
     static ref R_FIRSTLINE: Regex = Regex::new(r"(\d+)\s+(\S+)\s+=>\s+(\S+)B\s+(\S+)B\s+(\S+)B\s+(\S+)B").unwrap();
     //     2606:4700:20::6819:9766                  <=         0B     5.69KB     1.71KB     71.4KB
     static ref R_SECONDLINE: Regex = Regex::new(r"(\S+)\s+<=\s+(\S+)B\s+(\S+)B\s+(\S+)B\s+(\S+)B").unwrap();
+    // A byte value with its unit already split off, e.g. "20.3" + "K", or "0" + "".
+    static ref R_BYTE_VALUE: Regex = Regex::new(r"^([0-9]+(?:\.[0-9]+)?)([KMGT]?)$").unwrap();
+    // Total send rate:            0B       709B       457B
+    static ref R_TOTAL_SEND: Regex = Regex::new(r"^Total send rate:\s+(\S+)B\s+(\S+)B\s+(\S+)B$").unwrap();
+    // Total receive rate:           0B     5.69KB     1.71KB
+    static ref R_TOTAL_RECEIVE: Regex = Regex::new(r"^Total receive rate:\s+(\S+)B\s+(\S+)B\s+(\S+)B$").unwrap();
+    // Peak rate (sent/received/total):       450B        943B       1.37KB
+    static ref R_PEAK: Regex =
+        Regex::new(r"^Peak rate \(sent/received/total\):\s+(\S+)B\s+(\S+)B\s+(\S+)B$").unwrap();
+    // Cumulative (sent/received/total):    39.1KB      153KB      192KB
+    static ref R_CUMULATIVE: Regex =
+        Regex::new(r"^Cumulative \(sent/received/total\):\s+(\S+)B\s+(\S+)B\s+(\S+)B$").unwrap();
+}
+
+/// Parse a human-formatted byte count such as iftop's `"20.3K"` (the
+/// trailing `B` is expected to already be stripped by the caller) into an
+/// absolute byte count, rounded to the nearest byte.
+fn parse_bytes(s: &str) -> Option<u64> {
+    let caps = R_BYTE_VALUE.captures(s)?;
+    let value: f64 = caps.get(1)?.as_str().parse().ok()?;
+    let factor = match caps.get(2).map(|m| m.as_str()) {
+        Some("K") => 1024.0,
+        Some("M") => 1024.0 * 1024.0,
+        Some("G") => 1024.0 * 1024.0 * 1024.0,
+        Some("T") => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => 1.0,
+    };
+    Some((value * factor).round() as u64)
+}
+
+/// Wrap a line reader so an IO error (e.g. a broken pipe while the child is
+/// being killed for a graceful shutdown) ends the stream instead of
+/// panicking, giving the caller a chance to flush whatever was already parsed.
+fn reader_lines<R: BufRead>(reader: R) -> impl Iterator<Item = String> {
+    let mut lines = reader.lines();
+    std::iter::from_fn(move || match lines.next()? {
+        Ok(line) => Some(line),
+        Err(e) => {
+            tracing::warn!(error = %e, "stopping: failed to read a line from iftop's output");
+            None
+        }
+    })
 }
 
 fn main() {
-    let default_args = [
-        "-t", // text output
-        "-B", // output bytes, not bits
-        "-n", // no DNS reverse lookup
-        "-o", "40s", // sort by 40s column
-        "-s", "40", // gather for 40 seconds and then quit
+    let cli = Cli::parse();
+
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::from(cli.log_level))
+        .init();
+
+    let mut writer = format::from_name(cli.format.as_name())
+        .unwrap_or_else(|| panic!("unknown --format value: {}", cli.format.as_name()));
+
+    // The top-talkers report is its own shape, not an `Output`, so the
+    // per-record `Format` impls above don't apply to it; rather than
+    // silently ignoring `--format` in aggregate mode, refuse the combination.
+    if cli.aggregate.is_some() && !matches!(cli.format, cli::OutputFormat::Ndjson) {
+        eprintln!(
+            "--format {} is not supported with --aggregate; aggregate reports are always emitted as NDJSON",
+            cli.format.as_name()
+        );
+        std::process::exit(2);
+    }
+
+    let mut iftop_args = vec![
+        "-t".to_string(), // text output
+        "-B".to_string(), // output bytes, not bits
+        "-n".to_string(), // no DNS reverse lookup
+        "-o".to_string(),
+        cli.sort_column.as_iftop_arg().to_string(),
     ];
-    let passed_args: Vec<OsString> = env::args_os().skip(1).collect();
+    if cli.one_shot {
+        iftop_args.push("-s".to_string());
+        iftop_args.push(cli.sample_seconds.to_string());
+    }
+    if let Some(interface) = &cli.interface {
+        iftop_args.push("-i".to_string());
+        iftop_args.push(interface.clone());
+    }
+    iftop_args.extend(cli.extra_iftop_args.iter().cloned());
 
-    let iftop_args = passed_args
-        .iter()
-        .map(|x| x.as_os_str())
-        .chain(default_args.iter().map(OsStr::new));
-    let iftop = Command::new("/usr/sbin/iftop")
+    tracing::debug!(iftop_path = %cli.iftop_path, args = ?iftop_args, "spawning iftop");
+    let mut iftop = Command::new(&cli.iftop_path)
         .stdout(Stdio::piped())
-        .args(iftop_args)
+        .args(&iftop_args)
         .spawn()
-        .unwrap();
+        .unwrap_or_else(|e| panic!("failed to spawn {}: {}", cli.iftop_path, e));
+    let iftop_stdout = iftop.stdout.take().expect("iftop stdout was not piped");
+
+    // In streaming mode iftop runs until we kill it, so Ctrl-C/SIGTERM needs
+    // to reach the child explicitly rather than just ending our own process.
+    let iftop = Arc::new(Mutex::new(iftop));
+    {
+        let iftop = Arc::clone(&iftop);
+        ctrlc::set_handler(move || {
+            tracing::info!("shutting down, stopping iftop");
+            if let Ok(mut child) = iftop.lock() {
+                let _ = child.kill();
+            }
+        })
+        .expect("failed to install signal handler");
+    }
+
+    let input = BufReader::new(iftop_stdout);
+    let mut lines = reader_lines(input);
+
+    if let Some(window_seconds) = cli.aggregate {
+        let aggregator = Arc::new(Mutex::new(aggregate::Aggregator::new(window_seconds, cli.top_n)));
+
+        // Eviction and reporting run on their own timer, independent of
+        // sample arrival, so peers age out of the report even once the
+        // interface goes quiet and `timed_parse` stops returning anything.
+        let flush_seconds = cli.flush_seconds.max(1);
+        {
+            let aggregator = Arc::clone(&aggregator);
+            thread::spawn(move || loop {
+                thread::sleep(Duration::from_secs(flush_seconds));
+                let report = match aggregator.lock() {
+                    Ok(mut aggregator) => aggregator.flush(),
+                    Err(_) => break,
+                };
+                let stdout = io::stdout();
+                let mut out = stdout.lock();
+                match serde_json::to_writer(&mut out, &report) {
+                    Ok(()) => {
+                        let _ = writeln!(out);
+                    }
+                    Err(e) => tracing::warn!(error = %e, "failed to write aggregate report"),
+                }
+            });
+        }
+
+        loop {
+            match timed_parse(&mut lines, cli.keep_raw_bytes) {
+                Ok(Some(r)) => {
+                    if let Ok(mut aggregator) = aggregator.lock() {
+                        aggregator.ingest(&r);
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => tracing::warn!(error = %e, "failed to parse summary block, skipping"),
+            }
+        }
+    } else {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        loop {
+            match timed_parse(&mut lines, cli.keep_raw_bytes) {
+                Ok(Some(r)) => writer.write_record(&r, &mut out).unwrap(),
+                Ok(None) => break,
+                Err(e) => tracing::warn!(error = %e, "failed to parse summary block, skipping"),
+            }
+        }
+    }
+
+    if let Ok(mut child) = iftop.lock() {
+        let _ = child.wait();
+    };
+}
+
+/// Parse a byte-count capture group, logging a warning and returning `None`
+/// (rather than panicking) if iftop printed something `parse_bytes` can't
+/// make sense of.
+fn parse_bytes_field(raw: &str, field: &str) -> Option<u64> {
+    let value = parse_bytes(raw);
+    if value.is_none() {
+        tracing::warn!(field, raw, "could not parse byte count");
+    }
+    value
+}
 
-    let input = BufReader::new(iftop.stdout.unwrap());
-    let mut lines = input.lines().map(Result::unwrap);
+/// Build a `Record` out of a matched `=>`/`<=` line pair. Returns `Ok(None)`
+/// (rather than aborting the whole parse) if one of the byte columns is in a
+/// shape `parse_bytes` doesn't recognize.
+fn build_record(
+    firstline: &Captures,
+    secondline: &Captures,
+    keep_raw_bytes: bool,
+) -> Result<Option<Record>, ParseError> {
+    macro_rules! byte_field {
+        ($raw:expr, $name:literal) => {
+            match parse_bytes_field($raw, $name) {
+                Some(v) => v,
+                None => return Ok(None),
+            }
+        };
+    }
+
+    let outbound_2s_raw = firstline.get(3).unwrap().as_str();
+    let outbound_10s_raw = firstline.get(4).unwrap().as_str();
+    let outbound_40s_raw = firstline.get(5).unwrap().as_str();
+    let outbound_cumulative_raw = firstline.get(6).unwrap().as_str();
+    let inbound_2s_raw = secondline.get(2).unwrap().as_str();
+    let inbound_10s_raw = secondline.get(3).unwrap().as_str();
+    let inbound_40s_raw = secondline.get(4).unwrap().as_str();
+    let inbound_cumulative_raw = secondline.get(5).unwrap().as_str();
+
+    Ok(Some(Record {
+        rank: firstline.get(1).unwrap().as_str().parse()?,
+        local_name: firstline.get(2).unwrap().as_str().to_string(),
+        outbound_2s_bytes: byte_field!(outbound_2s_raw, "outbound_2s_bytes"),
+        outbound_10s_bytes: byte_field!(outbound_10s_raw, "outbound_10s_bytes"),
+        outbound_40s_bytes: byte_field!(outbound_40s_raw, "outbound_40s_bytes"),
+        outbound_40s_bytes_raw: keep_raw_bytes.then(|| outbound_40s_raw.to_string()),
+        outbound_cumulative_bytes: byte_field!(outbound_cumulative_raw, "outbound_cumulative_bytes"),
+        remote_name: secondline.get(1).unwrap().as_str().to_string(),
+        inbound_2s_bytes: byte_field!(inbound_2s_raw, "inbound_2s_bytes"),
+        inbound_10s_bytes: byte_field!(inbound_10s_raw, "inbound_10s_bytes"),
+        inbound_40s_bytes: byte_field!(inbound_40s_raw, "inbound_40s_bytes"),
+        inbound_40s_bytes_raw: keep_raw_bytes.then(|| inbound_40s_raw.to_string()),
+        inbound_cumulative_bytes: byte_field!(inbound_cumulative_raw, "inbound_cumulative_bytes"),
+    }))
+}
 
-    while let Some(r) = timed_parse(&mut lines) {
-        println!("{}", serde_json::to_string(&r).unwrap());
+/// Parse the three byte-count columns of a postamble total-rate line,
+/// logging a warning and substituting `0` for any column that doesn't parse.
+fn parse_totals_line(caps: &Captures, fields: [&'static str; 3]) -> [u64; 3] {
+    let mut values = [0u64; 3];
+    for (i, field) in fields.iter().enumerate() {
+        let raw = caps.get(i + 1).unwrap().as_str();
+        values[i] = parse_bytes_field(raw, field).unwrap_or(0);
     }
+    values
 }
 
-/// Continually parse an iterator of lines until either a blob of Record is parsed, or
-/// we run out of input.
+/// Continually parse an iterator of lines until either a blob of `Record`s
+/// is parsed, or we run out of input.
 ///
-/// This can panic if the input is malformed. We don't particularly care.
-fn parse_input<S: AsRef<str>, T: Iterator<Item = S>>(lines: &mut T) -> Option<Vec<Record>> {
+/// Malformed lines are logged and skipped rather than treated as fatal -
+/// one odd line from a long-running iftop process shouldn't kill this tool.
+fn parse_input<S: AsRef<str>, T: Iterator<Item = S>>(
+    lines: &mut T,
+    keep_raw_bytes: bool,
+) -> Result<Option<(Vec<Record>, Totals)>, ParseError> {
     let mut state = ParseState::Preamble;
     let mut records: Option<Vec<Record>> = None;
+    let mut totals = Totals::default();
 
     while let Some(l) = lines.next() {
-        state = match state {
+        match &mut state {
             ParseState::Preamble => {
                 if R_SEPARATOR.is_match(l.as_ref()) {
-                    ParseState::Records(Vec::new())
-                } else {
-                    state
+                    state = ParseState::Records(Vec::new());
                 }
             }
-            ParseState::Records(mut r) => {
+            ParseState::Records(r) => {
                 if R_SEPARATOR.is_match(l.as_ref()) {
-                    records = Some(r);
-                    ParseState::Postamble
+                    records = Some(std::mem::take(r));
+                    state = ParseState::Postamble;
                 } else if let Some(firstline) = R_FIRSTLINE.captures(l.as_ref()) {
-                    let l2 = lines.next().unwrap();
-                    if let Some(secondline) = R_SECONDLINE.captures(l2.as_ref()) {
-                        let record = Record {
-                            rank: firstline.get(1).unwrap().as_str().parse().unwrap(),
-                            local_name: firstline.get(2).unwrap().as_str().to_string(),
-                            outbound_40s_bytes: firstline.get(5).unwrap().as_str().to_string(),
-                            remote_name: secondline.get(1).unwrap().as_str().to_string(),
-                            inbound_40s_bytes: secondline.get(4).unwrap().as_str().to_string(),
-                        };
-
-                        r.push(record);
+                    match lines.next() {
+                        Some(l2) => {
+                            if let Some(secondline) = R_SECONDLINE.captures(l2.as_ref()) {
+                                if let Some(record) =
+                                    build_record(&firstline, &secondline, keep_raw_bytes)?
+                                {
+                                    r.push(record);
+                                }
+                            } else {
+                                tracing::warn!(
+                                    line = l2.as_ref(),
+                                    "expected a '<=' line after a '=>' line, skipping"
+                                );
+                            }
+                        }
+                        None => {
+                            tracing::warn!(
+                                "input ended after a '=>' line with no matching '<=' line"
+                            );
+                            break;
+                        }
                     }
-                    ParseState::Records(r)
                 } else {
-                    panic!("Unrecognized line in records input:\n{}", l.as_ref());
+                    tracing::warn!(
+                        line = l.as_ref(),
+                        "unrecognized line in records input, skipping"
+                    );
                 }
             }
             ParseState::Postamble => {
                 if R_END.is_match(l.as_ref()) {
                     break;
-                } else {
-                    state
+                } else if let Some(caps) = R_TOTAL_SEND.captures(l.as_ref()) {
+                    let [s2, s10, s40] = parse_totals_line(
+                        &caps,
+                        ["send_2s_bytes", "send_10s_bytes", "send_40s_bytes"],
+                    );
+                    totals.send_2s_bytes = s2;
+                    totals.send_10s_bytes = s10;
+                    totals.send_40s_bytes = s40;
+                } else if let Some(caps) = R_TOTAL_RECEIVE.captures(l.as_ref()) {
+                    let [r2, r10, r40] = parse_totals_line(
+                        &caps,
+                        ["receive_2s_bytes", "receive_10s_bytes", "receive_40s_bytes"],
+                    );
+                    totals.receive_2s_bytes = r2;
+                    totals.receive_10s_bytes = r10;
+                    totals.receive_40s_bytes = r40;
+                } else if let Some(caps) = R_PEAK.captures(l.as_ref()) {
+                    let [send, receive, total] = parse_totals_line(
+                        &caps,
+                        ["peak_send_bytes", "peak_receive_bytes", "peak_total_bytes"],
+                    );
+                    totals.peak_send_bytes = send;
+                    totals.peak_receive_bytes = receive;
+                    totals.peak_total_bytes = total;
+                } else if let Some(caps) = R_CUMULATIVE.captures(l.as_ref()) {
+                    let [send, receive, total] = parse_totals_line(
+                        &caps,
+                        [
+                            "cumulative_send_bytes",
+                            "cumulative_receive_bytes",
+                            "cumulative_total_bytes",
+                        ],
+                    );
+                    totals.cumulative_send_bytes = send;
+                    totals.cumulative_receive_bytes = receive;
+                    totals.cumulative_total_bytes = total;
                 }
             }
-        };
+        }
     }
 
-    records
+    // The input can end mid-block if the child was killed for a graceful
+    // shutdown; surface whatever records were already collected instead of
+    // silently dropping them.
+    if records.is_none() {
+        if let ParseState::Records(r) = &mut state {
+            if !r.is_empty() {
+                records = Some(std::mem::take(r));
+            }
+        }
+    }
+
+    Ok(records.map(|r| (r, totals)))
 }
 
-fn timed_parse<S: AsRef<str>, T: Iterator<Item = S>>(lines: &mut T) -> Option<Output> {
+fn timed_parse<S: AsRef<str>, T: Iterator<Item = S>>(
+    lines: &mut T,
+    keep_raw_bytes: bool,
+) -> Result<Option<Output>, ParseError> {
     let start_time = Utc::now();
-    let r = parse_input(lines);
+    let r = parse_input(lines, keep_raw_bytes)?;
 
-    r.map(|records| Output {
+    Ok(r.map(|(records, totals)| Output {
         start_time,
         records,
+        totals,
         end_time: Utc::now(),
-    })
+    }))
+}
+
+#[test]
+fn test_parse_bytes() {
+    assert_eq!(parse_bytes("0"), Some(0));
+    assert_eq!(parse_bytes("709"), Some(709));
+    assert_eq!(parse_bytes("20.3K"), Some(20787));
+    assert_eq!(parse_bytes("1.71K"), Some(1751));
+    assert_eq!(parse_bytes("garbage"), None);
 }
 
 #[test]
 fn test_parse_state() {
     let input = include_str!("../test/input1.txt");
     let mut lines = input.lines();
-    let r = parse_input(&mut lines).unwrap();
+    let (records, totals) = parse_input(&mut lines, false).unwrap().unwrap();
     let output = include_str!("../test/output1.txt").trim();
-    assert_eq!(output, serde_json::to_string(&r).unwrap());
+    assert_eq!(output, serde_json::to_string(&records).unwrap());
+
+    assert_eq!(totals.send_2s_bytes, 0);
+    assert_eq!(totals.send_10s_bytes, 709);
+    assert_eq!(totals.send_40s_bytes, 457);
+    assert_eq!(totals.receive_2s_bytes, 0);
+    assert_eq!(totals.receive_10s_bytes, 5827);
+    assert_eq!(totals.receive_40s_bytes, 1751);
+    assert_eq!(totals.peak_send_bytes, 450);
+    assert_eq!(totals.peak_receive_bytes, 943);
+    assert_eq!(totals.peak_total_bytes, 1403);
+    assert_eq!(totals.cumulative_send_bytes, 40038);
+    assert_eq!(totals.cumulative_receive_bytes, 156672);
+    assert_eq!(totals.cumulative_total_bytes, 196608);
 }
 
 enum ParseState {
@@ -130,17 +423,49 @@ enum ParseState {
 }
 
 #[derive(Debug, Serialize)]
-struct Record {
-    rank: u64,
-    local_name: String,
-    outbound_40s_bytes: String,
-    remote_name: String,
-    inbound_40s_bytes: String,
+pub(crate) struct Record {
+    pub(crate) rank: u64,
+    pub(crate) local_name: String,
+    pub(crate) outbound_2s_bytes: u64,
+    pub(crate) outbound_10s_bytes: u64,
+    pub(crate) outbound_40s_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) outbound_40s_bytes_raw: Option<String>,
+    /// Total bytes iftop has seen to this peer since it started, as opposed
+    /// to the rolling 2s/10s/40s *rate* columns above.
+    pub(crate) outbound_cumulative_bytes: u64,
+    pub(crate) remote_name: String,
+    pub(crate) inbound_2s_bytes: u64,
+    pub(crate) inbound_10s_bytes: u64,
+    pub(crate) inbound_40s_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) inbound_40s_bytes_raw: Option<String>,
+    /// Total bytes iftop has seen from this peer since it started.
+    pub(crate) inbound_cumulative_bytes: u64,
+}
+
+/// The interface-wide aggregates iftop prints in the postamble between the
+/// per-peer table and the closing `=====` line.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Totals {
+    pub(crate) send_2s_bytes: u64,
+    pub(crate) send_10s_bytes: u64,
+    pub(crate) send_40s_bytes: u64,
+    pub(crate) receive_2s_bytes: u64,
+    pub(crate) receive_10s_bytes: u64,
+    pub(crate) receive_40s_bytes: u64,
+    pub(crate) peak_send_bytes: u64,
+    pub(crate) peak_receive_bytes: u64,
+    pub(crate) peak_total_bytes: u64,
+    pub(crate) cumulative_send_bytes: u64,
+    pub(crate) cumulative_receive_bytes: u64,
+    pub(crate) cumulative_total_bytes: u64,
 }
 
 #[derive(Debug, Serialize)]
-struct Output {
-    start_time: DateTime<Utc>,
-    records: Vec<Record>,
-    end_time: DateTime<Utc>,
+pub(crate) struct Output {
+    pub(crate) start_time: DateTime<Utc>,
+    pub(crate) records: Vec<Record>,
+    pub(crate) totals: Totals,
+    pub(crate) end_time: DateTime<Utc>,
 }